@@ -9,13 +9,83 @@
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
 /// Things that we can store in the ring must have an ID string they advertise.
 pub trait HasId: std::fmt::Debug {
     fn id(&self) -> &str;
 }
 
-trait HashRing {
+/// A digest algorithm for placing replica keys on the ring. Swapping the hasher changes
+/// where every resource lands, so pick one and stick with it for a given ring — or
+/// supply the same one another system is using if you need to interoperate with it.
+pub trait RingHasher: std::fmt::Debug {
+    /// Hash arbitrary bytes down to the ring's key space.
+    fn hash_key(&self, bytes: &[u8]) -> u64;
+}
+
+/// The default [`RingHasher`]: truncates a blake3 digest to its first eight bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Blake3Hasher;
+
+impl RingHasher for Blake3Hasher {
+    fn hash_key(&self, bytes: &[u8]) -> u64 {
+        let digest = blake3::hash(bytes);
+        u64::from_le_bytes(
+            digest.as_bytes()[0..8]
+                .try_into()
+                .expect("a blake3 digest is always at least 8 bytes"),
+        )
+    }
+}
+
+/// A [`RingHasher`] using CRC-64/ECMA-182, the scheme the `consist` crate uses. Handy when
+/// you need a ring that's stable across toolchain versions or that agrees with another
+/// system's placement decisions.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Crc64EcmaHasher;
+
+impl RingHasher for Crc64EcmaHasher {
+    fn hash_key(&self, bytes: &[u8]) -> u64 {
+        crc64_ecma(bytes)
+    }
+}
+
+/// Reflected CRC-64/ECMA-182 (polynomial `0xC96C5795D7870F42`), computed with a
+/// lazily-built lookup table so repeated calls don't redo the bit-twiddling.
+fn crc64_ecma(bytes: &[u8]) -> u64 {
+    static TABLE: OnceLock<[u64; 256]> = OnceLock::new();
+    const POLY: u64 = 0xC96C_5795_D787_0F42;
+
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u64; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u64;
+            let mut j = 0;
+            while j < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ POLY
+                } else {
+                    crc >> 1
+                };
+                j += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    });
+
+    let mut crc = !0u64;
+    for byte in bytes {
+        let index = ((crc ^ *byte as u64) & 0xff) as usize;
+        crc = table[index] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub trait HashRing {
     /// This type represents the resources we are distributing around the hash ring.
     type A;
 
@@ -32,73 +102,68 @@ trait HashRing {
     fn resource_count(&self) -> usize;
     /// Total number of entries in the ring.
     fn len(&self) -> usize;
+    /// Whether the ring has no entries at all.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// How a sample key's owner changes under a simulated ring topology change, as returned
+/// by [`LightCycle::would_add`], [`LightCycle::would_add_weighted`], and
+/// [`LightCycle::would_remove`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveChange {
+    /// The key had no owner before the change (the ring was empty) and now resolves to `to`.
+    Gained { to: String },
+    /// The key had an owner before the change and has none after (the ring is now empty).
+    Lost { from: String },
+    /// The key's owner changed from `from` to `to`.
+    Moved { from: String, to: String },
+}
+
+/// A resource together with the number of virtual nodes it was actually given, so that
+/// `remove` can delete exactly the ring entries `add_weighted` put there.
+#[derive(Debug)]
+struct Entry<T: HasId> {
+    resource: T,
+    replica_count: usize,
 }
 
 /// A consistent hash ring with blue glowing lights.
 #[derive(Debug)]
-pub struct LightCycle {
-    /// The number of replicas of each resource to insert into the ring. Ring size = replicas * entries.
+pub struct LightCycle<T: HasId, H: RingHasher = Blake3Hasher> {
+    /// The default number of replicas of each resource to insert into the ring, before
+    /// any per-resource weight is applied.
     replicas: usize,
-    /// The resources we're tracking.
-    resources: HashMap<String, Box<dyn HasId>>,
+    /// The resources we're tracking, along with how many virtual nodes each was given.
+    resources: HashMap<String, Entry<T>>,
     /// The consistent hash ring itself: each entry points to a key in the resource map.
-    hashring: BTreeMap<String, String>,
+    hashring: BTreeMap<u64, String>,
+    /// The digest algorithm used to place replicas and incoming ids on the ring.
+    hasher: H,
 }
 
-impl Default for LightCycle {
+impl<T: HasId, H: RingHasher + Default> Default for LightCycle<T, H> {
     fn default() -> Self {
-        let replicas = 4; // defaulting to pretty small
-        let resources = HashMap::new();
-        let hashring = BTreeMap::new();
-
-        Self {
-            replicas,
-            resources,
-            hashring,
-        }
+        Self::new_with_replica_count(4) // defaulting to pretty small
     }
 }
 
-impl HashRing for LightCycle {
-    type A = Box<dyn HasId>;
+impl<T: HasId, H: RingHasher> HashRing for LightCycle<T, H> {
+    type A = T;
 
     fn add(&mut self, resource: Self::A) {
-        let id = resource.id();
-
-        for i in 0..self.replicas {
-            let hashitem = format!("{}{}", id.to_owned(), i);
-            let replica_id = blake3::hash(hashitem.as_bytes()).to_string();
-            self.hashring.insert(replica_id, id.to_owned());
-        }
-
-        self.resources.insert(id.to_owned(), resource);
+        self.add_weighted(resource, 1);
     }
 
     fn remove(&mut self, resource: &Self::A) {
-        let id = resource.id();
-        for i in 0..self.replicas {
-            let hashitem = format!("{}{}", id.to_owned(), i);
-            let replica_id = blake3::hash(hashitem.as_bytes()).to_string();
-            self.hashring.remove(&replica_id);
-        }
-        self.resources.remove(id);
+        self.remove_by_id(resource.id());
     }
 
     fn locate(&self, id: &str) -> Option<&Self::A> {
-        let hashed_id = blake3::hash(id.as_bytes()).to_string();
-
-        // This search is the heart of the consistent hash ring concept.
-        // The data structure we use for the hashring has to be something
-        // that maintains a lexical ordering and lets us do this search.
-        if let Some((_hash, resource_id)) = self.hashring.iter().find(|(k, _v)| k >= &&hashed_id) {
-            self.resources.get(resource_id)
-        } else if let Some((_hash, resource_id)) = self.hashring.last_key_value() {
-            // We're past the end, so we take the last node.
-            self.resources.get(resource_id)
-        } else {
-            // This case happens if the ring is empty. People who do that get what they deserve.
-            None
-        }
+        let hashed_id = self.hasher.hash_key(id.as_bytes());
+        let resource_id = Self::resolve(&self.hashring, hashed_id)?;
+        self.resources.get(resource_id).map(|entry| &entry.resource)
     }
 
     fn resource_count(&self) -> usize {
@@ -110,13 +175,176 @@ impl HashRing for LightCycle {
     }
 }
 
-impl LightCycle {
+impl<T: HasId, H: RingHasher + Default> LightCycle<T, H> {
     pub fn new_with_replica_count(replicas: usize) -> Self {
+        Self::new_with_replica_count_and_hasher(replicas, H::default())
+    }
+}
+
+impl<T: HasId, H: RingHasher> LightCycle<T, H> {
+    /// Build a ring with the default replica count, using a caller-supplied hasher. Use
+    /// this to match the hashing scheme of a ring you need to interoperate with.
+    pub fn new_with_hasher(hasher: H) -> Self {
+        Self::new_with_replica_count_and_hasher(4, hasher)
+    }
+
+    pub fn new_with_replica_count_and_hasher(replicas: usize, hasher: H) -> Self {
         Self {
             replicas,
             resources: HashMap::new(),
             hashring: BTreeMap::new(),
+            hasher,
+        }
+    }
+
+    /// Walk a ring clockwise from `hashed_id`, wrapping around to the first entry if we
+    /// run off the end, and hand back the resource id it lands on. This is the heart of
+    /// the consistent hash ring concept, factored out so simulated rings in
+    /// [`Self::would_add_weighted`] and [`Self::would_remove`] can share it with `locate`.
+    fn resolve(ring: &BTreeMap<u64, String>, hashed_id: u64) -> Option<&str> {
+        if let Some((_hash, resource_id)) = ring.range(hashed_id..).next() {
+            Some(resource_id)
+        } else if let Some((_hash, resource_id)) = ring.iter().next() {
+            // We're past the end of the ring, so we wrap around to the first
+            // node going clockwise rather than falling back to the last one.
+            Some(resource_id)
+        } else {
+            // This case happens if the ring is empty. People who do that get what they deserve.
+            None
+        }
+    }
+
+    /// For each of `keys`, report how its owner would change if `resource` were added
+    /// with `replicas * weight` virtual nodes, without actually mutating the ring. Keys
+    /// whose owner would be unaffected are omitted from the result.
+    ///
+    /// Use this before calling [`Self::add_weighted`] to gauge the relocation fraction a
+    /// topology change would cause.
+    pub fn would_add_weighted<'k>(
+        &self,
+        keys: &[&'k str],
+        resource: &T,
+        weight: usize,
+    ) -> Vec<(&'k str, MoveChange)> {
+        let id = resource.id();
+        let mut scratch = self.hashring.clone();
+
+        // If `id` is already on the ring, this is a weight change rather than a fresh
+        // add: drop its existing virtual nodes from the scratch ring first so we don't
+        // simulate leaving the old, differently-sized replica set behind.
+        if let Some(existing) = self.resources.get(id) {
+            for i in 0..existing.replica_count {
+                let hashitem = format!("{}{}", id, i);
+                let replica_id = self.hasher.hash_key(hashitem.as_bytes());
+                scratch.remove(&replica_id);
+            }
+        }
+
+        let replica_count = self.replicas * weight;
+        for i in 0..replica_count {
+            let hashitem = format!("{}{}", id, i);
+            let replica_id = self.hasher.hash_key(hashitem.as_bytes());
+            scratch.insert(replica_id, id.to_owned());
+        }
+
+        self.changed_keys(keys, &scratch)
+    }
+
+    /// For each of `keys`, report how its owner would change if `resource` were added
+    /// with the ring's default replica count. Equivalent to
+    /// `would_add_weighted(keys, resource, 1)`.
+    pub fn would_add<'k>(&self, keys: &[&'k str], resource: &T) -> Vec<(&'k str, MoveChange)> {
+        self.would_add_weighted(keys, resource, 1)
+    }
+
+    /// For each of `keys`, report how its owner would change if the resource with the
+    /// given id were removed, without actually mutating the ring. Keys whose owner would
+    /// be unaffected are omitted from the result. Returns an empty `Vec` if no resource
+    /// with that id is on the ring.
+    ///
+    /// Use this before calling [`Self::remove_by_id`] to gauge the relocation fraction a
+    /// topology change would cause.
+    pub fn would_remove<'k>(&self, keys: &[&'k str], id: &str) -> Vec<(&'k str, MoveChange)> {
+        let Some(entry) = self.resources.get(id) else {
+            return Vec::new();
+        };
+
+        let mut scratch = self.hashring.clone();
+        for i in 0..entry.replica_count {
+            let hashitem = format!("{}{}", id, i);
+            let replica_id = self.hasher.hash_key(hashitem.as_bytes());
+            scratch.remove(&replica_id);
+        }
+
+        self.changed_keys(keys, &scratch)
+    }
+
+    /// Compare where each key resolves on the live ring against where it resolves on
+    /// `scratch`, a hypothetical ring, and report only the keys whose owner differs.
+    fn changed_keys<'k>(
+        &self,
+        keys: &[&'k str],
+        scratch: &BTreeMap<u64, String>,
+    ) -> Vec<(&'k str, MoveChange)> {
+        keys.iter()
+            .filter_map(|&key| {
+                let hashed_id = self.hasher.hash_key(key.as_bytes());
+                let before = Self::resolve(&self.hashring, hashed_id);
+                let after = Self::resolve(scratch, hashed_id);
+
+                let change = match (before, after) {
+                    (None, Some(to)) => MoveChange::Gained { to: to.to_owned() },
+                    (Some(from), None) => MoveChange::Lost {
+                        from: from.to_owned(),
+                    },
+                    (Some(from), Some(to)) if from != to => MoveChange::Moved {
+                        from: from.to_owned(),
+                        to: to.to_owned(),
+                    },
+                    _ => return None,
+                };
+                Some((key, change))
+            })
+            .collect()
+    }
+
+    /// Add a resource with a weight relative to the ring's default replica count, giving
+    /// it `replicas * weight` virtual nodes instead of the usual `replicas`. Use this to
+    /// give a beefier resource a proportionally larger share of keys. `add` is equivalent
+    /// to `add_weighted(resource, 1)`.
+    pub fn add_weighted(&mut self, resource: T, weight: usize) {
+        let id = resource.id().to_owned();
+        // If `id` is already on the ring (e.g. the caller is rebalancing a resource to a
+        // new weight), drop its existing virtual nodes first so none are orphaned: left
+        // behind in `hashring` but no longer reachable via `resources`.
+        self.remove_by_id(&id);
+        let replica_count = self.replicas * weight;
+
+        for i in 0..replica_count {
+            let hashitem = format!("{}{}", id, i);
+            let replica_id = self.hasher.hash_key(hashitem.as_bytes());
+            self.hashring.insert(replica_id, id.clone());
         }
+
+        self.resources.insert(
+            id,
+            Entry {
+                resource,
+                replica_count,
+            },
+        );
+    }
+
+    /// Remove the resource with the given id and hand back ownership of it, deleting
+    /// exactly the ring entries it was given. Returns `None` if no such resource exists.
+    pub fn remove_by_id(&mut self, id: &str) -> Option<T> {
+        let entry = self.resources.remove(id)?;
+        for i in 0..entry.replica_count {
+            let hashitem = format!("{}{}", id, i);
+            let replica_id = self.hasher.hash_key(hashitem.as_bytes());
+            self.hashring.remove(&replica_id);
+        }
+        Some(entry.resource)
     }
 }
 
@@ -161,13 +389,13 @@ mod tests {
     fn locations_behave_as_expected() {
         // This test knows about how we generate id hashes.
         // First, make a zero-replicas ring.
-        let mut ring = LightCycle::new_with_replica_count(1);
-        ring.add(Box::new(MockResource {
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(1);
+        ring.add(MockResource {
             name: "pecan".to_string(),
-        }));
-        ring.add(Box::new(MockResource {
+        });
+        ring.add(MockResource {
             name: "walnut".to_string(),
-        }));
+        });
 
         let location = ring.locate("pecan0").unwrap();
         assert_eq!(location.id(), "pecan");
@@ -180,10 +408,10 @@ mod tests {
     fn adding_new_replicas_moves_locations() {
         let fruits = pick_some_fruit();
         let mut fruit_iter = fruits.into_iter();
-        let mut ring = LightCycle::new_with_replica_count(2);
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(2);
 
         let f = fruit_iter.next().unwrap();
-        ring.add(Box::new(f));
+        ring.add(f);
         assert_eq!(ring.len(), 2);
         assert_eq!(ring.resource_count(), 1);
 
@@ -193,7 +421,7 @@ mod tests {
         assert_eq!(location.id(), "apple");
 
         for f in fruit_iter {
-            ring.add(Box::new(f));
+            ring.add(f);
         }
 
         assert_eq!(ring.len(), FRUITS.len() * 2);
@@ -202,26 +430,26 @@ mod tests {
         let location = ring
             .locate("nom nom nom")
             .expect("everything should have a home of some kind");
-        assert_eq!(location.id(), "pear");
+        assert_eq!(location.id(), "litchi");
 
         let location = ring
             .locate("asdfasdfasdfsafasdf")
             .expect("everything should have a home of some kind");
-        assert_eq!(location.id(), "orange");
+        assert_eq!(location.id(), "apple");
 
         let location = ring
             .locate("1")
             .expect("everything should have a home of some kind");
-        assert_eq!(location.id(), "mangosteen");
+        assert_eq!(location.id(), "kumquat");
     }
 
     #[test]
     fn single_node_rings() {
-        let mut ring = LightCycle::new_with_replica_count(5);
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(5);
         let durian = MockResource {
             name: "durian".to_string(),
         };
-        ring.add(Box::new(durian)); // nobody likes being next to durian
+        ring.add(durian); // nobody likes being next to durian
         let location = ring
             .locate("a")
             .expect("everything should have a home of some kind");
@@ -232,18 +460,35 @@ mod tests {
         assert_eq!(location.id(), "durian");
     }
 
+    #[test]
+    fn alternate_hashers_still_locate() {
+        let mut ring: LightCycle<MockResource, Crc64EcmaHasher> =
+            LightCycle::new_with_replica_count(3);
+        ring.add(MockResource {
+            name: "pecan".to_string(),
+        });
+        ring.add(MockResource {
+            name: "walnut".to_string(),
+        });
+
+        let location = ring
+            .locate("a cache key")
+            .expect("everything should have a home of some kind");
+        assert!(location.id() == "pecan" || location.id() == "walnut");
+    }
+
     #[test]
     fn adding_same_resource_twice() {
         let fruits = pick_some_fruit();
-        let mut ring = LightCycle::new_with_replica_count(5);
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(5);
         for f in fruits.clone().into_iter() {
-            ring.add(Box::new(f));
+            ring.add(f);
         }
         assert_eq!(ring.len(), FRUITS.len() * 5);
         assert_eq!(ring.resource_count(), FRUITS.len());
 
         for f in fruits.into_iter() {
-            ring.add(Box::new(f));
+            ring.add(f);
         }
         assert_eq!(
             ring.len(),
@@ -256,4 +501,129 @@ mod tests {
             "adding resources we already have should be a no-op"
         );
     }
+
+    #[test]
+    fn weighted_resources_get_proportional_ring_presence() {
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(4);
+        ring.add(MockResource {
+            name: "small".to_string(),
+        });
+        ring.add_weighted(
+            MockResource {
+                name: "big".to_string(),
+            },
+            3,
+        );
+
+        assert_eq!(ring.len(), 4 + 4 * 3);
+        assert_eq!(ring.resource_count(), 2);
+
+        let removed = ring
+            .remove_by_id("big")
+            .expect("the resource we just added should still be there");
+        assert_eq!(removed.id(), "big");
+
+        assert_eq!(
+            ring.len(),
+            4,
+            "removing a weighted resource should delete every virtual node it was given"
+        );
+        assert_eq!(ring.resource_count(), 1);
+    }
+
+    #[test]
+    fn add_weighted_again_replaces_the_old_replica_set() {
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(4);
+        ring.add_weighted(
+            MockResource {
+                name: "big".to_string(),
+            },
+            3,
+        );
+        assert_eq!(ring.len(), 4 * 3);
+
+        // Rebalancing "big" down to a smaller weight shouldn't leave its old, larger
+        // replica set behind in the ring.
+        ring.add_weighted(
+            MockResource {
+                name: "big".to_string(),
+            },
+            1,
+        );
+        assert_eq!(
+            ring.len(),
+            4,
+            "re-adding a resource with a new weight should replace its old virtual nodes, not add to them"
+        );
+        assert_eq!(ring.resource_count(), 1);
+
+        let removed = ring
+            .remove_by_id("big")
+            .expect("the resource we just added should still be there");
+        assert_eq!(removed.id(), "big");
+        assert_eq!(
+            ring.len(),
+            0,
+            "removing should delete every remaining virtual node, with none orphaned from the earlier weight"
+        );
+    }
+
+    #[test]
+    fn would_add_reports_only_keys_that_move() {
+        let fruits = pick_some_fruit();
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(2);
+        for f in fruits {
+            ring.add(f);
+        }
+
+        let keys = ["nom nom nom", "asdfasdfasdfsafasdf", "1"];
+        let newcomer = MockResource {
+            name: "starfruit".to_string(),
+        };
+
+        let before: Vec<_> = keys
+            .iter()
+            .map(|k| ring.locate(k).unwrap().id().to_owned())
+            .collect();
+
+        let changes = ring.would_add(&keys, &newcomer);
+
+        // Simulating shouldn't have mutated the real ring.
+        assert_eq!(ring.resource_count(), FRUITS.len());
+        for (k, before) in keys.iter().zip(before.iter()) {
+            assert_eq!(ring.locate(k).unwrap().id(), before);
+        }
+
+        for (key, change) in changes {
+            match change {
+                MoveChange::Moved { from, to } => {
+                    assert_ne!(from, to);
+                    assert_eq!(to, "starfruit");
+                }
+                other => panic!("unexpected change for {key}: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn would_remove_reports_only_keys_that_move() {
+        let fruits = pick_some_fruit();
+        let mut ring: LightCycle<MockResource> = LightCycle::new_with_replica_count(2);
+        for f in fruits {
+            ring.add(f);
+        }
+
+        let keys = ["nom nom nom", "asdfasdfasdfsafasdf", "1"];
+        let changes = ring.would_remove(&keys, "litchi");
+
+        assert_eq!(ring.resource_count(), FRUITS.len());
+        for (key, change) in &changes {
+            match change {
+                MoveChange::Moved { from, .. } => assert_eq!(from, "litchi"),
+                other => panic!("unexpected change for {key}: {other:?}"),
+            }
+        }
+
+        assert!(ring.would_remove(&keys, "no-such-resource").is_empty());
+    }
 }